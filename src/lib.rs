@@ -1,11 +1,34 @@
+use std::collections::HashMap;
 use std::fs;
+use std::str::FromStr;
 
-use chess::{BitBoard, Board, ChessMove, Square};
+use chess::{
+    BitBoard, Board, BoardStatus, ChessMove, Color, MoveGen, Piece, Rank, Square, EMPTY,
+};
 use vizia::{image, prelude::*};
 
+mod pgn;
+
+/// Piece-set / board themes selectable at runtime. Each name is both the
+/// sprite sub-directory (`./assets/sprites/{theme}/`) and a board-colour CSS
+/// class (`theme-{theme}`).
+const THEMES: [&str; 3] = ["classic", "wood", "blue"];
+
 enum ChessEvent {
     TileClicked(i32),
     ToggleFlipping,
+    PromotionChosen(Piece),
+    LoadFen(String),
+    CopyFen,
+    StepBack,
+    StepForward,
+    JumpToStart,
+    JumpToEnd,
+    ToggleOpponent,
+    ToggleGreedy,
+    EngineMove,
+    ExportPgn,
+    SetTheme(String),
     Reset,
 }
 
@@ -16,12 +39,32 @@ pub struct Chess {
     selected: Option<(i32, bool)>,
     on_check: Option<(i32, bool)>,
     should_flip: bool,
+    promoting: Option<(Square, Square)>,
+    error: Option<String>,
+    fen_input: String,
+    history: Vec<Board>,
+    moves: Vec<ChessMove>,
+    ply_cursor: usize,
+    legal_targets: BitBoard,
+    opponent: Option<Color>,
+    greedy: bool,
+    pgn_output: String,
+    position_counts: HashMap<u64, u8>,
+    halfmove_clock: u32,
+    draw_reason: Option<&'static str>,
+    theme: String,
 }
 
 impl View for Chess {
-    fn event(&mut self, _cx: &mut EventContext, event: &mut Event) {
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
         event.map(|chess_event, meta| match chess_event {
             ChessEvent::TileClicked(pos) => {
+                // Ignore board input while reviewing history or once the game
+                // has been drawn by repetition or the fifty-move rule.
+                if self.ply_cursor + 1 != self.history.len() || self.draw_reason.is_some() {
+                    meta.consume();
+                    return;
+                }
                 let pos = if self.should_flip && self.board.side_to_move() == chess::Color::Black {
                     63 - pos
                 } else {
@@ -39,11 +82,23 @@ impl View for Chess {
                     } else {
                         let to = pos_board.to_square();
                         let from = unsafe { Square::new(selected_pos as u8) };
-                        let new_move = ChessMove::new(from, to, None);
-                        if self.board.legal(new_move) {
-                            self.board = self.board.make_move_new(new_move);
-                            self.update_board();
-                            self.selected = None;
+                        let promotion_rank = match self.board.side_to_move() {
+                            chess::Color::White => Rank::Eighth,
+                            chess::Color::Black => Rank::First,
+                        };
+                        if self.board.piece_on(from) == Some(Piece::Pawn)
+                            && to.get_rank() == promotion_rank
+                        {
+                            self.promoting = Some((from, to));
+                        } else {
+                            let new_move = ChessMove::new(from, to, None);
+                            if self.board.legal(new_move) {
+                                self.apply_move(new_move);
+                                self.selected = None;
+                                if self.opponents_turn() {
+                                    cx.emit(ChessEvent::EngineMove);
+                                }
+                            }
                         }
                     }
                 } else {
@@ -55,17 +110,124 @@ impl View for Chess {
                         ));
                     }
                 }
+                self.recompute_targets();
                 meta.consume();
             }
             ChessEvent::ToggleFlipping => {
                 self.should_flip ^= true;
+                self.recompute_targets();
+                self.update_board();
+                meta.consume();
+            }
+            ChessEvent::PromotionChosen(piece) => {
+                if let Some((from, to)) = self.promoting {
+                    let new_move = ChessMove::new(from, to, Some(*piece));
+                    if self.board.legal(new_move) {
+                        self.apply_move(new_move);
+                        if self.opponents_turn() {
+                            cx.emit(ChessEvent::EngineMove);
+                        }
+                    }
+                }
+                self.promoting = None;
+                self.selected = None;
+                self.recompute_targets();
+                meta.consume();
+            }
+            ChessEvent::LoadFen(fen) => {
+                match Board::from_str(fen.trim()) {
+                    Ok(board) => {
+                        self.board = board;
+                        self.selected = None;
+                        self.promoting = None;
+                        self.error = None;
+                        self.history = vec![board];
+                        self.moves = Vec::new();
+                        self.ply_cursor = 0;
+                        self.recompute_targets();
+                        self.refresh_draw_state();
+                        self.update_board();
+                    }
+                    Err(err) => self.error = Some(format!("Invalid FEN: {}", err)),
+                }
+                meta.consume();
+            }
+            ChessEvent::CopyFen => {
+                self.fen_input = self.board.to_string();
+                meta.consume();
+            }
+            ChessEvent::StepBack => {
+                if self.ply_cursor > 0 {
+                    self.ply_cursor -= 1;
+                    self.jump_to_cursor();
+                }
+                meta.consume();
+            }
+            ChessEvent::StepForward => {
+                if self.ply_cursor + 1 < self.history.len() {
+                    self.ply_cursor += 1;
+                    self.jump_to_cursor();
+                }
+                meta.consume();
+            }
+            ChessEvent::JumpToStart => {
+                self.ply_cursor = 0;
+                self.jump_to_cursor();
+                meta.consume();
+            }
+            ChessEvent::JumpToEnd => {
+                self.ply_cursor = self.history.len() - 1;
+                self.jump_to_cursor();
+                meta.consume();
+            }
+            ChessEvent::ToggleOpponent => {
+                // Single-player toggles an engine playing the Black pieces.
+                self.opponent = match self.opponent {
+                    Some(_) => None,
+                    None => Some(Color::Black),
+                };
+                if self.opponents_turn() {
+                    cx.emit(ChessEvent::EngineMove);
+                }
+                meta.consume();
+            }
+            ChessEvent::ToggleGreedy => {
+                self.greedy ^= true;
+                meta.consume();
+            }
+            ChessEvent::EngineMove => {
+                // Only reply while the game is live and it really is our turn.
+                if self.opponents_turn() && self.ply_cursor + 1 == self.history.len() {
+                    if let Some(reply) = self.engine_move() {
+                        self.apply_move(reply);
+                        self.selected = None;
+                        self.recompute_targets();
+                    }
+                }
+                meta.consume();
+            }
+            ChessEvent::SetTheme(theme) => {
+                self.theme = theme.clone();
+                // Regenerate the sprite keys so the new piece set is loaded.
                 self.update_board();
                 meta.consume();
             }
+            ChessEvent::ExportPgn => {
+                self.pgn_output = pgn::to_pgn(&self.history[0], &self.moves);
+                meta.consume();
+            }
             ChessEvent::Reset => {
                 self.board = Board::default();
+                self.history = vec![Board::default()];
+                self.moves = Vec::new();
+                self.ply_cursor = 0;
+                self.pgn_output = String::new();
                 self.update_board();
                 self.selected = None;
+                self.promoting = None;
+                self.error = None;
+                self.recompute_targets();
+                self.refresh_draw_state();
                 meta.consume();
             }
         });
@@ -76,10 +238,24 @@ impl Chess {
     pub fn new(cx: &mut Context) -> Handle<Self> {
         Self {
             board: Board::default(),
-            images: get_paths_from_pos(&Board::default()),
+            images: get_paths_from_pos(&Board::default(), THEMES[0]),
             selected: None,
             on_check: None,
             should_flip: true,
+            promoting: None,
+            error: None,
+            fen_input: Board::default().to_string(),
+            history: vec![Board::default()],
+            moves: Vec::new(),
+            ply_cursor: 0,
+            legal_targets: EMPTY,
+            opponent: None,
+            greedy: false,
+            pgn_output: String::new(),
+            position_counts: HashMap::from([(Board::default().get_hash(), 1)]),
+            halfmove_clock: 0,
+            draw_reason: None,
+            theme: THEMES[0].to_string(),
         }
         .build(cx, |cx| {
             cx.add_stylesheet("./assets/stylesheets/styles.css")
@@ -114,6 +290,11 @@ impl Chess {
                         cx,
                         Chess::board.map(|value| format!("{:?}", value.side_to_move())),
                     );
+                    Label::new(
+                        cx,
+                        Chess::draw_reason
+                            .map(|reason| reason.map(String::from).unwrap_or_default()),
+                    );
                 })
                 .class("board-state");
                 // Board
@@ -137,6 +318,15 @@ impl Chess {
                                             None => false,
                                         }),
                                     )
+                                    .toggle_class(
+                                        "legal-move",
+                                        Chess::legal_targets.map(move |value| {
+                                            let index = ((7 - y) * 8 + x) as u8;
+                                            *value & BitBoard::from_square(unsafe {
+                                                Square::new(index)
+                                            }) != EMPTY
+                                        }),
+                                    )
                                     .on_press(move |cx| {
                                         cx.emit(ChessEvent::TileClicked((7 - y) * 8 + x))
                                     })
@@ -156,7 +346,10 @@ impl Chess {
                         .class("board-row");
                     }
                 })
-                .class("board");
+                .class("board")
+                .toggle_class("theme-classic", Chess::theme.map(|theme| theme == THEMES[0]))
+                .toggle_class("theme-wood", Chess::theme.map(|theme| theme == THEMES[1]))
+                .toggle_class("theme-blue", Chess::theme.map(|theme| theme == THEMES[2]));
 
                 HStack::new(cx, |cx| {
                     Button::new(
@@ -169,13 +362,228 @@ impl Chess {
                     Label::new(cx, "Board flipping");
                 })
                 .class("board-settings");
+
+                // Single-player options
+                HStack::new(cx, |cx| {
+                    Checkbox::new(cx, Chess::opponent.map(|opponent| opponent.is_some()))
+                        .on_toggle(|cx| cx.emit(ChessEvent::ToggleOpponent));
+                    Label::new(cx, "Single player");
+                    Checkbox::new(cx, Chess::greedy)
+                        .on_toggle(|cx| cx.emit(ChessEvent::ToggleGreedy));
+                    Label::new(cx, "Greedy engine");
+
+                    // Theme selector
+                    Dropdown::new(
+                        cx,
+                        |cx| Label::new(cx, Chess::theme),
+                        |cx| {
+                            for theme in THEMES {
+                                Label::new(cx, theme).class("theme-option").on_press(
+                                    move |cx| {
+                                        cx.emit(ChessEvent::SetTheme(theme.to_string()));
+                                        cx.emit(PopupEvent::Close);
+                                    },
+                                );
+                            }
+                        },
+                    );
+                })
+                .class("board-settings");
+
+                // Playback navigation
+                HStack::new(cx, |cx| {
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ChessEvent::JumpToStart),
+                        |cx| Label::new(cx, "<<").color(Color::white()),
+                    );
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ChessEvent::StepBack),
+                        |cx| Label::new(cx, "<").color(Color::white()),
+                    );
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ChessEvent::StepForward),
+                        |cx| Label::new(cx, ">").color(Color::white()),
+                    );
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ChessEvent::JumpToEnd),
+                        |cx| Label::new(cx, ">>").color(Color::white()),
+                    );
+                })
+                .class("board-settings");
+
+                // FEN load/save
+                HStack::new(cx, |cx| {
+                    Textbox::new(cx, Chess::fen_input)
+                        .width(Stretch(1.0))
+                        .on_submit(|cx, text, _| cx.emit(ChessEvent::LoadFen(text)));
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ChessEvent::CopyFen),
+                        |cx| Label::new(cx, "Copy FEN").color(Color::white()),
+                    );
+                })
+                .class("board-settings");
+
+                // PGN export
+                HStack::new(cx, |cx| {
+                    Textbox::new(cx, Chess::pgn_output).width(Stretch(1.0));
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(ChessEvent::ExportPgn),
+                        |cx| Label::new(cx, "Export PGN").color(Color::white()),
+                    );
+                })
+                .class("board-settings");
+                Binding::new(cx, Chess::error, |cx, error| {
+                    if let Some(message) = error.get(cx) {
+                        Label::new(cx, &message).class("error");
+                    }
+                });
+
+                // Pawn promotion picker, shown only while a promotion is pending
+                Binding::new(cx, Chess::promoting, |cx, promoting| {
+                    if let Some((_, to)) = promoting.get(cx) {
+                        let color = match to.get_rank() {
+                            Rank::Eighth => chess::Color::White,
+                            _ => chess::Color::Black,
+                        };
+                        let theme = Chess::theme.get(cx);
+                        HStack::new(cx, |cx| {
+                            for piece in
+                                [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight]
+                            {
+                                Element::new(cx)
+                                    .class("promotion-choice")
+                                    .image(format!("{}/{}", theme, piece.to_string(color)))
+                                    .on_press(move |cx| {
+                                        cx.emit(ChessEvent::PromotionChosen(piece))
+                                    });
+                            }
+                        })
+                        .class("promotion-dialog");
+                    }
+                });
             })
             .class("board-frame");
         })
     }
 
+    fn apply_move(&mut self, new_move: ChessMove) {
+        self.board = self.board.make_move_new(new_move);
+        self.history.truncate(self.ply_cursor + 1);
+        self.moves.truncate(self.ply_cursor);
+        self.history.push(self.board);
+        self.moves.push(new_move);
+        self.ply_cursor += 1;
+        self.refresh_draw_state();
+        self.update_board();
+    }
+
+    fn jump_to_cursor(&mut self) {
+        self.board = self.history[self.ply_cursor];
+        self.selected = None;
+        self.recompute_targets();
+        self.refresh_draw_state();
+        self.update_board();
+    }
+
+    /// Recompute repetition counts and the halfmove clock for the current line
+    /// (`history[0..=ply_cursor]`) and set `draw_reason` when threefold
+    /// repetition or the fifty-move rule applies. Rebuilding from the line
+    /// keeps the counts correct across undo/redo and forked variations.
+    fn refresh_draw_state(&mut self) {
+        let mut counts: HashMap<u64, u8> = HashMap::new();
+        let mut clock: u32 = 0;
+        for ply in 0..=self.ply_cursor {
+            let board = &self.history[ply];
+            *counts.entry(board.get_hash()).or_insert(0) += 1;
+            if ply > 0 {
+                let before = &self.history[ply - 1];
+                let captured = board.combined().popcnt() < before.combined().popcnt();
+                let pawn_changed = before.pieces(Piece::Pawn) != board.pieces(Piece::Pawn);
+                if captured || pawn_changed {
+                    clock = 0;
+                } else {
+                    clock += 1;
+                }
+            }
+        }
+        self.draw_reason = if counts.values().any(|count| *count >= 3) {
+            Some("Threefold repetition")
+        } else if clock >= 100 {
+            Some("Fifty-move rule")
+        } else {
+            None
+        };
+        self.position_counts = counts;
+        self.halfmove_clock = clock;
+    }
+
+    /// Whether the built-in engine should move in the current position.
+    fn opponents_turn(&self) -> bool {
+        self.opponent == Some(self.board.side_to_move())
+            && self.board.status() == BoardStatus::Ongoing
+            && self.draw_reason.is_none()
+    }
+
+    /// Pick a reply for the engine: any legal move, or greedily by material
+    /// when the "greedy" setting is on (ties broken the same way).
+    ///
+    /// The choice is the Zobrist hash of the position modulo the number of
+    /// candidates, so it varies between positions but the same position
+    /// always gets the same reply; there is no random number generator.
+    fn engine_move(&self) -> Option<ChessMove> {
+        let moves: Vec<ChessMove> = MoveGen::new_legal(&self.board).collect();
+        if moves.is_empty() {
+            return None;
+        }
+        let seed = self.board.get_hash() as usize;
+        if !self.greedy {
+            return Some(moves[seed % moves.len()]);
+        }
+        let side = self.board.side_to_move();
+        let mut best_score = i32::MIN;
+        let mut best_moves = Vec::new();
+        for chess_move in &moves {
+            let score = material_balance(&self.board.make_move_new(*chess_move), side);
+            if score > best_score {
+                best_score = score;
+                best_moves.clear();
+                best_moves.push(*chess_move);
+            } else if score == best_score {
+                best_moves.push(*chess_move);
+            }
+        }
+        Some(best_moves[seed % best_moves.len()])
+    }
+
+    /// Rebuild the set of squares the currently selected piece may move to.
+    ///
+    /// The bits are stored in display coordinates (matching the flip applied
+    /// to `on_check`/`selected`) so the view can test them directly.
+    fn recompute_targets(&mut self) {
+        let mut targets = EMPTY;
+        if let Some((selected_pos, _)) = self.selected {
+            let flipped =
+                self.should_flip && self.board.side_to_move() == chess::Color::Black;
+            let source = unsafe { Square::new(selected_pos as u8) };
+            for chess_move in MoveGen::new_legal(&self.board) {
+                if chess_move.get_source() == source {
+                    let dest = chess_move.get_dest().to_int() as i32;
+                    let index = if flipped { 63 - dest } else { dest };
+                    targets |= BitBoard::from_square(unsafe { Square::new(index as u8) });
+                }
+            }
+        }
+        self.legal_targets = targets;
+    }
+
     fn update_board(&mut self) {
-        self.images = get_paths_from_pos(&self.board);
+        self.images = get_paths_from_pos(&self.board, &self.theme);
         if self.should_flip && self.board.side_to_move() == chess::Color::Black {
             self.images.reverse();
         }
@@ -191,7 +599,26 @@ impl Chess {
     }
 }
 
-fn get_paths_from_pos(board: &Board) -> [String; 64] {
+/// Material of `side` minus material of its opponent, using the classic
+/// P=1, N=B=3, R=5, Q=9 weighting.
+fn material_balance(board: &Board, side: Color) -> i32 {
+    let weights = [
+        (Piece::Pawn, 1),
+        (Piece::Knight, 3),
+        (Piece::Bishop, 3),
+        (Piece::Rook, 5),
+        (Piece::Queen, 9),
+    ];
+    let mut score = 0;
+    for (piece, value) in weights {
+        let own = (board.color_combined(side) & board.pieces(piece)).popcnt() as i32;
+        let enemy = (board.color_combined(!side) & board.pieces(piece)).popcnt() as i32;
+        score += value * (own - enemy);
+    }
+    score
+}
+
+fn get_paths_from_pos(board: &Board, theme: &str) -> [String; 64] {
     board
         .to_string()
         .trim()
@@ -204,7 +631,9 @@ fn get_paths_from_pos(board: &Board) -> [String; 64] {
             if "12345678".contains(c) {
                 vec![String::new(); c.to_digit(10).expect("Should be a digit") as usize]
             } else {
-                vec![String::from(c)]
+                // Prefix the sprite key with the theme so switching themes
+                // loads a fresh bitmap instead of the cached one.
+                vec![format!("{}/{}", theme, c)]
             }
         })
         .collect::<Vec<String>>()
@@ -0,0 +1,139 @@
+//! Export a played game to Standard Algebraic Notation (PGN movetext).
+//!
+//! Every move is rendered against the board *before* it is played, which is
+//! what SAN disambiguation, capture detection and check markers all depend on.
+
+use chess::{Board, BoardStatus, ChessMove, File, MoveGen, Piece, Rank, EMPTY};
+
+/// Render the sequence of `moves` (starting from `start`) as PGN movetext,
+/// e.g. `1. e4 e5 2. Nf3 Nc6`.
+pub fn to_pgn(start: &Board, moves: &[ChessMove]) -> String {
+    let mut board = *start;
+    let mut out = String::new();
+    for (ply, chess_move) in moves.iter().enumerate() {
+        if ply % 2 == 0 {
+            if ply > 0 {
+                out.push(' ');
+            }
+            out.push_str(&format!("{}. ", ply / 2 + 1));
+        } else {
+            out.push(' ');
+        }
+        out.push_str(&san(&board, *chess_move));
+        board = board.make_move_new(*chess_move);
+    }
+    out
+}
+
+/// Standard Algebraic Notation for `chess_move` in the position `board`.
+fn san(board: &Board, chess_move: ChessMove) -> String {
+    let source = chess_move.get_source();
+    let dest = chess_move.get_dest();
+    let piece = board
+        .piece_on(source)
+        .expect("A move must originate from an occupied square");
+
+    // Castling is detected by the king travelling two files.
+    if piece == Piece::King {
+        let file_delta = dest.get_file().to_index() as i32 - source.get_file().to_index() as i32;
+        if file_delta == 2 {
+            return with_suffix(board, chess_move, String::from("O-O"));
+        } else if file_delta == -2 {
+            return with_suffix(board, chess_move, String::from("O-O-O"));
+        }
+    }
+
+    let is_pawn = piece == Piece::Pawn;
+    // A pawn only changes file when it captures; en passant lands on an empty
+    // square, so a file change is what flags a pawn capture in that case.
+    let is_capture =
+        board.piece_on(dest).is_some() || (is_pawn && source.get_file() != dest.get_file());
+
+    let mut san = String::new();
+    if is_pawn {
+        if is_capture {
+            san.push(file_char(source.get_file()));
+        }
+    } else {
+        san.push(piece_letter(piece));
+        san.push_str(&disambiguation(board, chess_move, piece));
+    }
+
+    if is_capture {
+        san.push('x');
+    }
+    san.push(file_char(dest.get_file()));
+    san.push(rank_char(dest.get_rank()));
+
+    if let Some(promotion) = chess_move.get_promotion() {
+        san.push('=');
+        san.push(piece_letter(promotion));
+    }
+
+    with_suffix(board, chess_move, san)
+}
+
+/// Minimal source-square qualifier needed when another piece of the same type
+/// can also reach the destination: file, else rank, else both.
+fn disambiguation(board: &Board, chess_move: ChessMove, piece: Piece) -> String {
+    let source = chess_move.get_source();
+    let dest = chess_move.get_dest();
+    let (mut same_file, mut same_rank, mut ambiguous) = (false, false, false);
+    for other in MoveGen::new_legal(board) {
+        if other.get_dest() == dest
+            && other.get_source() != source
+            && board.piece_on(other.get_source()) == Some(piece)
+        {
+            ambiguous = true;
+            if other.get_source().get_file() == source.get_file() {
+                same_file = true;
+            }
+            if other.get_source().get_rank() == source.get_rank() {
+                same_rank = true;
+            }
+        }
+    }
+
+    let mut out = String::new();
+    if ambiguous {
+        if !same_file {
+            out.push(file_char(source.get_file()));
+        } else if !same_rank {
+            out.push(rank_char(source.get_rank()));
+        } else {
+            out.push(file_char(source.get_file()));
+            out.push(rank_char(source.get_rank()));
+        }
+    }
+    out
+}
+
+/// Append the check (`+`) or checkmate (`#`) marker for the resulting position.
+fn with_suffix(board: &Board, chess_move: ChessMove, mut san: String) -> String {
+    let after = board.make_move_new(chess_move);
+    if after.status() == BoardStatus::Checkmate {
+        san.push('#');
+    } else if *after.checkers() != EMPTY {
+        san.push('+');
+    }
+    san
+}
+
+fn piece_letter(piece: Piece) -> char {
+    match piece {
+        Piece::Pawn => 'P',
+        Piece::Knight => 'N',
+        Piece::Bishop => 'B',
+        Piece::Rook => 'R',
+        Piece::Queen => 'Q',
+        Piece::King => 'K',
+    }
+}
+
+fn file_char(file: File) -> char {
+    (b'a' + file.to_index() as u8) as char
+}
+
+fn rank_char(rank: Rank) -> char {
+    (b'1' + rank.to_index() as u8) as char
+}